@@ -27,13 +27,162 @@ use std::collections::{
     hash_map::Entry::{Occupied, Vacant},
     HashMap, HashSet,
 };
-use tantivy::{schema::Field, Index, IndexWriter};
-use tracing::warn;
+use tantivy::merge_policy::{LogMergePolicy, MergePolicy, NoMergePolicy};
+use tantivy::{schema::Field, Index, IndexWriter, Opstamp, Term, UserOperation};
+use tracing::{debug, warn};
+
+/// Minimum whatlang confidence required to trust a per-document detection. Below this,
+/// the value stays on its field's default tokenizer rather than being routed to a
+/// language-specific analyzer.
+const LANGUAGE_DETECTION_MIN_CONFIDENCE: f64 = 0.5;
+
+/// Detect the dominant language of `text`, returning its ISO 639-3 code and the
+/// classifier's confidence. Returns `None` when the input is too short or ambiguous
+/// for the trigram/script classifier to produce a guess.
+fn detect_language(text: &str) -> Option<(String, f64)> {
+    whatlang::detect(text).map(|info| (info.lang().code().to_string(), info.confidence()))
+}
+
+/// For each field configured for language detection, detect the dominant language of
+/// its text value, route the value to the matching per-language tokenizer (registered
+/// during `setup_tokenizers`), and stamp the detected ISO code onto the document's
+/// language field so it can be filtered on. Values below the confidence threshold, or
+/// languages without a configured tokenizer, keep the field's default tokenizer.
+fn apply_language_detection(schema: &SearchIndexSchema, document: &mut SearchDocument) {
+    for field in schema.language_detection_fields() {
+        let Some(text) = document.text_value(field.source) else {
+            continue;
+        };
+        let Some((lang, confidence)) = detect_language(&text) else {
+            continue;
+        };
+        if confidence < LANGUAGE_DETECTION_MIN_CONFIDENCE {
+            continue;
+        }
+        if let Some(tokenizer) = field.tokenizer_for(&lang) {
+            document.set_tokenizer(field.source, tokenizer);
+        }
+        if let Some(language_field) = field.language_field {
+            document.insert_text(language_field, lang);
+        }
+    }
+}
+
+/// On-disk format/engine version stamped into `SearchIndex` metadata at creation time.
+/// Bump this whenever a change to the serialized schema or the underlying Tantivy
+/// format makes previously-written indexes unreadable by this build.
+pub const CURRENT_INDEX_FORMAT_VERSION: u32 = 1;
+
+/// Tantivy requires each indexing thread to own at least this many bytes of arena.
+const HEAP_SIZE_MIN: usize = 3_000_000;
+/// Tantivy reserves roughly this much of the arena for its own bookkeeping, so the
+/// overall budget must stay this far below `u32::MAX`.
+const HEAP_MARGIN: usize = 1_000_000;
+
+/// Memory arena size and thread count used to build an `IndexWriter`.
+///
+/// These map directly onto Tantivy's `Index::writer_with_num_threads(num_threads,
+/// overall_heap_bytes)`. Tuning them lets callers trade RAM/CPU for throughput on
+/// large bulk loads instead of being stuck on the conservative defaults.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriterSettings {
+    pub num_threads: usize,
+    pub overall_heap_bytes: usize,
+}
+
+impl WriterSettings {
+    /// Validate against the invariants Tantivy enforces: at least one thread, a
+    /// per-thread arena of at least [`HEAP_SIZE_MIN`], and an overall budget that
+    /// stays below `u32::MAX` minus the [`HEAP_MARGIN`].
+    fn validate(&self) -> Result<(), IndexError> {
+        if self.num_threads == 0 {
+            return Err(IndexError::InvalidWriterSettings(
+                "num_threads must be at least 1".into(),
+            ));
+        }
+        if self.overall_heap_bytes / self.num_threads < HEAP_SIZE_MIN {
+            return Err(IndexError::InvalidWriterSettings(format!(
+                "per-thread heap ({} bytes) is below the {HEAP_SIZE_MIN} byte minimum",
+                self.overall_heap_bytes / self.num_threads
+            )));
+        }
+        if self.overall_heap_bytes >= (u32::MAX as usize) - HEAP_MARGIN {
+            return Err(IndexError::InvalidWriterSettings(format!(
+                "overall heap ({} bytes) must stay below u32::MAX - {HEAP_MARGIN}",
+                self.overall_heap_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Per-index segment merge policy. Maps onto Tantivy's `LogMergePolicy` knobs, or
+/// selects a no-merge policy that leaves segments untouched — useful during a bulk
+/// load that is followed by an explicit `WriterRequest::Merge` pass.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergePolicyConfig {
+    /// Leave segments alone; consolidate later with an explicit merge.
+    NoMerge,
+    /// Tantivy's logarithmic merge policy. `None` fields keep Tantivy's defaults.
+    Log {
+        min_merge_size: Option<usize>,
+        min_layer_size: Option<u32>,
+        level_log_size: Option<f64>,
+        max_merge_size: Option<usize>,
+    },
+}
+
+impl MergePolicyConfig {
+    /// Build the Tantivy merge policy this config describes.
+    fn build(&self) -> Box<dyn MergePolicy> {
+        match self {
+            MergePolicyConfig::NoMerge => Box::new(NoMergePolicy),
+            MergePolicyConfig::Log {
+                min_merge_size,
+                min_layer_size,
+                level_log_size,
+                max_merge_size,
+            } => {
+                let mut policy = LogMergePolicy::default();
+                if let Some(min_merge_size) = min_merge_size {
+                    policy.set_min_num_segments(*min_merge_size);
+                }
+                if let Some(min_layer_size) = min_layer_size {
+                    policy.set_min_layer_size(*min_layer_size);
+                }
+                if let Some(level_log_size) = level_log_size {
+                    policy.set_level_log_size(*level_log_size);
+                }
+                if let Some(max_merge_size) = max_merge_size {
+                    policy.set_max_docs_before_merge(*max_merge_size);
+                }
+                Box::new(policy)
+            }
+        }
+    }
+}
+
+/// A single insert or delete within a [`WriterRequest::Batch`]. Mixing both in one
+/// request lets a statement touching many rows be applied atomically through a single
+/// run of `UserOperation`s.
+pub enum BatchOp {
+    Insert { document: SearchDocument },
+    Delete { field: Field, ctid: u64 },
+}
 
 /// The entity that interfaces with Tantivy indexes.
 pub struct Writer {
     /// Map of index directory path to Tantivy writer instance.
     tantivy_writers: HashMap<WriterDirectory, IndexWriter>,
+    /// Per-index writer tuning. When an entry changes, the cached writer for that
+    /// directory is discarded so the next `get_writer` rebuilds it with the new budget.
+    writer_settings: HashMap<WriterDirectory, WriterSettings>,
+    /// Per-index merge policy, applied to each freshly-built writer. Changing an entry
+    /// discards the cached writer so the next `get_writer` rebuilds it with the policy.
+    merge_policies: HashMap<WriterDirectory, MergePolicyConfig>,
+    /// Cached schema per index, used to drive per-document language detection without
+    /// re-reading the serialized `SearchIndex` on every insert.
+    schemas: HashMap<WriterDirectory, SearchIndexSchema>,
     drop_requested: HashSet<WriterDirectory>,
 }
 
@@ -41,32 +190,241 @@ impl Writer {
     pub fn new() -> Self {
         Self {
             tantivy_writers: HashMap::new(),
+            writer_settings: HashMap::new(),
+            merge_policies: HashMap::new(),
+            schemas: HashMap::new(),
             drop_requested: HashSet::new(),
         }
     }
 
+    /// Return the cached schema for an index, loading it from disk on first use.
+    fn schema_for(
+        &mut self,
+        directory: &WriterDirectory,
+    ) -> Result<&SearchIndexSchema, IndexError> {
+        match self.schemas.entry(directory.clone()) {
+            Vacant(entry) => Ok(entry.insert(directory.load_index()?.schema)),
+            Occupied(entry) => Ok(entry.into_mut()),
+        }
+    }
+
     /// Check the writer server cache for an existing IndexWriter. If it does not exist,
     /// then retrieve the SearchIndex and use it to create a new IndexWriter, caching it.
+    ///
+    /// Errors if the directory was mounted read-only: such indexes are opened through
+    /// their `IndexReader` only and never instantiate an `IndexWriter`, so no mutation
+    /// (and no `INDEX_WRITER_LOCK` acquisition) is possible against them.
     fn get_writer(&mut self, directory: WriterDirectory) -> Result<&mut IndexWriter, IndexError> {
+        if directory.readonly {
+            return Err(IndexError::ReadOnlyDirectory(directory));
+        }
         match self.tantivy_writers.entry(directory.clone()) {
             Vacant(entry) => {
-                Ok(entry.insert(SearchIndex::writer(&directory).map_err(|err| {
-                    IndexError::GetWriterFailed(directory.clone(), err.to_string())
-                })?))
+                // Compare the stamped format version against this build before opening.
+                // A mismatch means the on-disk layout was written by an incompatible
+                // build (older or newer than this one); report it distinctly
+                // so callers can recover via `WriterRequest::Reindex`.
+                Self::ensure_format_current(&directory)?;
+                let writer = match self.writer_settings.get(&directory) {
+                    Some(settings) => SearchIndex::writer_with_settings(&directory, settings)
+                        .map_err(|err| Self::classify_open_error(&directory, err))?,
+                    None => SearchIndex::writer(&directory)
+                        .map_err(|err| Self::classify_open_error(&directory, err))?,
+                };
+                // Apply the configured merge policy before the writer starts merging.
+                if let Some(policy) = self.merge_policies.get(&directory) {
+                    writer.set_merge_policy(policy.build());
+                }
+                Ok(entry.insert(writer))
             }
             Occupied(entry) => Ok(entry.into_mut()),
         }
     }
 
-    fn insert(
+    /// Compare the format version stamped into the serialized `SearchIndex` against
+    /// [`CURRENT_INDEX_FORMAT_VERSION`]. A mismatch means the index was written by an
+    /// incompatible build, so we surface [`IndexError::OutdatedIndexFormat`] before ever
+    /// handing the directory to Tantivy.
+    fn ensure_format_current(directory: &WriterDirectory) -> Result<(), IndexError> {
+        // `SearchIndex::format_version` is `#[serde(default)]`, so an index written
+        // before the field existed deserializes with version 0 and trips the mismatch
+        // below. Should the stored metadata be too old to deserialize at all, we still
+        // treat it as an outdated format rather than surfacing an opaque error, so the
+        // `WriterRequest::Reindex` recovery path can fire.
+        match directory.load_index() {
+            Ok(stored) if stored.format_version == CURRENT_INDEX_FORMAT_VERSION => Ok(()),
+            Ok(_) | Err(_) => Err(IndexError::OutdatedIndexFormat(directory.clone())),
+        }
+    }
+
+    /// Translate an index-open failure into a more specific error. An `InvalidData`
+    /// I/O error surfacing from Tantivy means the on-disk format predates this build,
+    /// so we report [`IndexError::OutdatedIndexFormat`] (which callers can recover from
+    /// via `WriterRequest::Reindex`) rather than an opaque I/O failure.
+    fn classify_open_error<E: std::error::Error + 'static>(
+        directory: &WriterDirectory,
+        err: E,
+    ) -> IndexError {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&err);
+        while let Some(cause) = source {
+            if let Some(io_err) = cause.downcast_ref::<std::io::Error>() {
+                if io_err.kind() == std::io::ErrorKind::InvalidData {
+                    return IndexError::OutdatedIndexFormat(directory.clone());
+                }
+            }
+            source = cause.source();
+        }
+        IndexError::GetWriterFailed(directory.clone(), err.to_string())
+    }
+
+    /// Drop the physical Tantivy directory for an index whose on-disk format is no
+    /// longer readable and rebuild it from the schema stored in the serialized
+    /// `SearchIndex` metadata. This lets operators recover from format drift across
+    /// engine upgrades without manually dropping and recreating the index.
+    fn reindex(&mut self, directory: WriterDirectory) -> Result<(), IndexError> {
+        if directory.readonly {
+            return Err(IndexError::ReadOnlyDirectory(directory));
+        }
+
+        // Read back the schema and identity we stamped at create time.
+        let stored = directory.load_index()?;
+
+        // Rebuild into a sibling temp directory first. Only once that succeeds do we
+        // discard the existing index, so a failed rebuild can't leave the index with
+        // its data already gone.
+        let tantivy_dir_path = directory.tantivy_dir_path(true)?;
+        let rebuild_dir_path = tantivy_dir_path.with_extension("reindex");
+        std::fs::remove_dir_all(&rebuild_dir_path).ok();
+        std::fs::create_dir_all(&rebuild_dir_path)?;
+        {
+            let mut rebuilt = Index::builder()
+                .schema(stored.schema.schema.clone())
+                .create_in_dir(&rebuild_dir_path)?;
+            SearchIndex::setup_tokenizers(&mut rebuilt, &stored.schema);
+        }
+
+        // The rebuild is on disk; swap it in. Drop the cached writer, remove the old
+        // directory, then rename the rebuild into its place. The swap is two steps, not
+        // one atomic rename: if the rename fails, the old directory is already gone.
+        if let Some(writer) = self.tantivy_writers.remove(&directory) {
+            std::mem::drop(writer);
+        }
+        std::fs::remove_dir_all(&tantivy_dir_path).ok();
+        std::fs::rename(&rebuild_dir_path, &tantivy_dir_path)?;
+
+        // Invalidate the cached schema so language detection routes on the rebuilt one.
+        self.schemas.remove(&directory);
+
+        let mut underlying_index = Index::open_in_dir(&tantivy_dir_path)?;
+        SearchIndex::setup_tokenizers(&mut underlying_index, &stored.schema);
+
+        let new_self = SearchIndex {
+            reader: SearchIndex::reader(&underlying_index)?,
+            underlying_index,
+            directory: directory.clone(),
+            schema: stored.schema,
+            uuid: stored.uuid,
+            format_version: CURRENT_INDEX_FORMAT_VERSION,
+            is_dirty: false,
+            is_pending_drop: false,
+            is_pending_create: true,
+        };
+
+        new_self.directory.save_index(&new_self)?;
+        Ok(())
+    }
+
+    /// Record writer tuning and/or merge policy for an index, validating the settings
+    /// and discarding any cached writer so the next `get_writer` rebuilds it with the
+    /// new memory/thread budget and merge policy. `None` fields leave the existing
+    /// configuration untouched.
+    fn configure(
         &mut self,
         directory: WriterDirectory,
-        document: SearchDocument,
+        settings: Option<WriterSettings>,
+        merge_policy: Option<MergePolicyConfig>,
     ) -> Result<(), IndexError> {
+        if directory.readonly {
+            return Err(IndexError::ReadOnlyDirectory(directory));
+        }
+
+        let mut changed = false;
+
+        if let Some(settings) = settings {
+            settings.validate()?;
+            if self.writer_settings.get(&directory) != Some(&settings) {
+                self.writer_settings.insert(directory.clone(), settings);
+                changed = true;
+            }
+        }
+
+        if let Some(merge_policy) = merge_policy {
+            if self.merge_policies.get(&directory) != Some(&merge_policy) {
+                self.merge_policies.insert(directory.clone(), merge_policy);
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.tantivy_writers.remove(&directory);
+        }
+        Ok(())
+    }
+
+    /// Force consolidation of the index's segments. This merges all current segments
+    /// into one, complementing `vacuum` and letting callers run an explicit merge pass
+    /// after a bulk load performed under a no-merge policy.
+    fn merge(&mut self, directory: WriterDirectory) -> Result<(), IndexError> {
         let writer = self.get_writer(directory)?;
-        // Add the Tantivy document to the index.
-        writer.add_document(document.into())?;
+        let segment_ids = writer.index().searchable_segment_ids()?;
+        if segment_ids.len() > 1 {
+            writer.merge(&segment_ids).wait()?;
+        }
+        Ok(())
+    }
 
+    /// Apply a batch of mixed insert/delete operations atomically. Tantivy's
+    /// `IndexWriter::run` assigns the whole batch a contiguous opstamp range and
+    /// returns the single opstamp that tracks its commit ordering, which also avoids
+    /// the per-document queue overhead of repeated `add_document`/`delete_term` calls.
+    fn batch(
+        &mut self,
+        directory: WriterDirectory,
+        mut ops: Vec<BatchOp>,
+    ) -> Result<Opstamp, IndexError> {
+        // Route inserted documents to language-specific tokenizers before they reach
+        // the writer. Skipped entirely when the schema configures no detected fields.
+        {
+            let schema = self.schema_for(&directory)?;
+            if schema.has_language_detection() {
+                for op in &mut ops {
+                    if let BatchOp::Insert { document } = op {
+                        apply_language_detection(schema, document);
+                    }
+                }
+            }
+        }
+
+        let writer = self.get_writer(directory)?;
+        let user_ops = ops
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Insert { document } => UserOperation::Add(document.into()),
+                BatchOp::Delete { field, ctid } => {
+                    UserOperation::Delete(Term::from_field_u64(field, ctid))
+                }
+            })
+            .collect::<Vec<_>>();
+        let opstamp = writer.run(user_ops)?;
+        Ok(opstamp)
+    }
+
+    fn insert(
+        &mut self,
+        directory: WriterDirectory,
+        document: SearchDocument,
+    ) -> Result<(), IndexError> {
+        self.batch(directory, vec![BatchOp::Insert { document }])?;
         Ok(())
     }
 
@@ -76,11 +434,14 @@ impl Writer {
         ctid_field: &Field,
         ctid_values: &[u64],
     ) -> Result<(), IndexError> {
-        let writer = self.get_writer(directory)?;
-        for ctid in ctid_values {
-            let ctid_term = tantivy::Term::from_field_u64(*ctid_field, *ctid);
-            writer.delete_term(ctid_term);
-        }
+        let ops = ctid_values
+            .iter()
+            .map(|ctid| BatchOp::Delete {
+                field: *ctid_field,
+                ctid: *ctid,
+            })
+            .collect();
+        self.batch(directory, ops)?;
         Ok(())
     }
 
@@ -133,7 +494,17 @@ impl Writer {
         fields: Vec<(SearchFieldName, SearchFieldConfig, SearchFieldType)>,
         uuid: String,
         key_field_index: usize,
+        settings: Option<WriterSettings>,
+        merge_policy: Option<MergePolicyConfig>,
     ) -> Result<()> {
+        if directory.readonly {
+            return Err(IndexError::ReadOnlyDirectory(directory).into());
+        }
+
+        if settings.is_some() || merge_policy.is_some() {
+            self.configure(directory.clone(), settings, merge_policy)?;
+        }
+
         let schema = SearchIndexSchema::new(fields, key_field_index)?;
 
         let tantivy_dir_path = directory.tantivy_dir_path(true)?;
@@ -150,6 +521,7 @@ impl Writer {
             directory: directory.clone(),
             schema,
             uuid,
+            format_version: CURRENT_INDEX_FORMAT_VERSION,
             is_dirty: false,
             is_pending_drop: false,
             is_pending_create: true,
@@ -160,12 +532,22 @@ impl Writer {
         Ok(())
     }
 
-    /// Physically delete the Tantivy directory. This should only be called on commit.
-    fn drop_index_on_commit(&mut self, directory: WriterDirectory) -> Result<(), IndexError> {
-        if let Some(writer) = self.tantivy_writers.remove(&directory) {
+    /// Drop every cached entry the server holds for a directory. Called when an index
+    /// is physically removed so a long-lived `Writer` doesn't leak per-directory state
+    /// across create/drop cycles, and so a reused directory key can't inherit a dropped
+    /// index's settings, merge policy, or schema.
+    fn forget_directory(&mut self, directory: &WriterDirectory) {
+        if let Some(writer) = self.tantivy_writers.remove(directory) {
             std::mem::drop(writer);
-        };
+        }
+        self.writer_settings.remove(directory);
+        self.merge_policies.remove(directory);
+        self.schemas.remove(directory);
+    }
 
+    /// Physically delete the Tantivy directory. This should only be called on commit.
+    fn drop_index_on_commit(&mut self, directory: WriterDirectory) -> Result<(), IndexError> {
+        self.forget_directory(&directory);
         directory.remove()?;
         Ok(())
     }
@@ -191,19 +573,43 @@ impl Handler<WriterRequest> for Writer {
                 field,
                 ctids,
             } => Ok(self.delete(directory, &field, &ctids)?),
+            WriterRequest::Batch { directory, ops } => {
+                // `Handler::handle` is `Result<()>`, so the batch opstamp can't be
+                // returned through the trait; record it so the contiguous opstamp the
+                // batch occupies is observable for commit-ordering diagnostics.
+                let opstamp = self.batch(directory.clone(), ops)?;
+                debug!(?directory, opstamp, "applied atomic batch");
+                Ok(())
+            }
             WriterRequest::CreateIndex {
                 directory,
                 fields,
                 uuid,
                 key_field_index,
+                settings,
+                merge_policy,
             } => {
-                self.create_index(directory, fields, uuid, key_field_index)?;
+                self.create_index(
+                    directory,
+                    fields,
+                    uuid,
+                    key_field_index,
+                    settings,
+                    merge_policy,
+                )?;
                 Ok(())
             }
+            WriterRequest::Configure {
+                directory,
+                settings,
+                merge_policy,
+            } => Ok(self.configure(directory, settings, merge_policy)?),
+            WriterRequest::Reindex { directory } => Ok(self.reindex(directory)?),
             WriterRequest::DropIndex { directory } => Ok(self.drop_index(directory)?),
             WriterRequest::Commit { directory } => Ok(self.commit(directory)?),
             WriterRequest::Abort { directory } => Ok(self.abort(directory)?),
             WriterRequest::Vacuum { directory } => Ok(self.vacuum(directory)?),
+            WriterRequest::Merge { directory } => Ok(self.merge(directory)?),
         }
     }
 }